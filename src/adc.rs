@@ -0,0 +1,34 @@
+//! adc.rs
+//! Periodic photodiode sampling for closed-loop brightness regulation:
+//! reads the single-ended ADC channel the photodiode is wired to at a fixed
+//! rate and feeds each reading to `regulate`, which drives whichever
+//! channels are currently in `RegulationMode::Regulated`.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use embassy_time::{Duration, Timer};
+use embedded_hal_0_2::adc::OneShot;
+use rp235x_hal::adc::{Adc, AdcPin};
+use rp235x_hal::gpio::bank0::Gpio26;
+use rp235x_hal::gpio::{FloatingInput, Pin};
+
+/// How often the photodiode is sampled and fed to the PID loop(s). Fast
+/// enough that regulation tracks a brightness drift long before a human
+/// would notice it, without contending with anything else on the bus.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Full-scale reading of the RP2350's 12-bit ADC.
+const ADC_MAX: u32 = 0x0fff;
+
+/// Samples the photodiode pin in a loop, scales each 12-bit reading to the
+/// same 0..=0xffff range `CustomHidCommand` and the ramp engine use, and
+/// forwards it to [`crate::regulate::on_measurement`].
+#[embassy_executor::task]
+pub async fn adc_task(mut adc: Adc, mut pin: AdcPin<Pin<Gpio26, FloatingInput>>) -> ! {
+    loop {
+        let raw: u16 = adc.read(&mut pin).unwrap_or(0);
+        let measurement = ((raw as u32 * u16::MAX as u32) / ADC_MAX) as u16;
+        crate::regulate::on_measurement(measurement);
+
+        Timer::after(SAMPLE_INTERVAL).await;
+    }
+}
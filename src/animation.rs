@@ -0,0 +1,205 @@
+//! animation.rs
+//! Timer-driven brightness ramps: a hardware alarm ticks at
+//! [`ANIMATION_TICK_HZ`] and advances each channel's current duty toward its
+//! target along an eased curve ([`EASE_TABLE`]), so `StartRamp` transitions
+//! land smoothly over the requested duration instead of jumping instantly. An
+//! instant `CustomHidCommand` (from the legacy HID/serial `SetAll` paths)
+//! still snaps straight to its target, clearing any ramp in progress.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use fugit::ExtU32;
+use rp235x_hal::pac::{self, interrupt};
+use rp235x_hal::timer::{Alarm, Alarm0};
+
+use crate::hidcust::CustomHidCommand;
+use crate::protocol::ChannelId;
+
+/// How often the ramp engine advances each channel.
+pub const ANIMATION_TICK_HZ: u32 = 1000;
+
+/// Ease-in/out lookup table: `EASE_TABLE[i]` is the percent of the way from
+/// start to target after `i / 16` of a ramp's duration has elapsed
+/// (smoothstep, `3t^2 - 2t^3`). Index 16 is the final, fully-settled value.
+const EASE_TABLE: [u8; 17] = [
+    0, 1, 4, 9, 16, 23, 32, 41, 50, 59, 68, 77, 84, 91, 96, 99, 100,
+];
+
+#[derive(Clone, Copy)]
+struct ChannelAnim {
+    start: i32,
+    target: i32,
+    elapsed_ticks: u32,
+    total_ticks: u32,
+    eased: bool,
+}
+
+impl ChannelAnim {
+    const fn idle(value: u16) -> Self {
+        Self {
+            start: value as i32,
+            target: value as i32,
+            elapsed_ticks: 0,
+            total_ticks: 0,
+            eased: false,
+        }
+    }
+
+    fn current(&self) -> u16 {
+        if self.total_ticks == 0 || self.elapsed_ticks >= self.total_ticks {
+            return self.target as u16;
+        }
+        let percent = if self.eased {
+            let index = (self.elapsed_ticks as usize * 16) / self.total_ticks as usize;
+            EASE_TABLE[index.min(16)] as i32
+        } else {
+            (self.elapsed_ticks as i64 * 100 / self.total_ticks as i64) as i32
+        };
+        (self.start + (self.target - self.start) * percent / 100) as u16
+    }
+
+    /// Advances the ramp by one tick. No-op once it has settled.
+    fn tick(&mut self) {
+        if self.elapsed_ticks < self.total_ticks {
+            self.elapsed_ticks += 1;
+        }
+    }
+
+    fn start_ramp(&mut self, target: u16, duration_ticks: u32, eased: bool) {
+        self.start = self.current() as i32;
+        self.target = target as i32;
+        self.elapsed_ticks = 0;
+        self.total_ticks = duration_ticks;
+        self.eased = eased;
+    }
+
+    fn set_instant(&mut self, value: u16) {
+        self.start = value as i32;
+        self.target = value as i32;
+        self.elapsed_ticks = 0;
+        self.total_ticks = 0;
+    }
+}
+
+struct AnimState {
+    wh: ChannelAnim,
+    ir: ChannelAnim,
+    uv: ChannelAnim,
+    alarm: Alarm0,
+    /// Last value handed out by `take_if_changed`, so the foreground task
+    /// can poll cheaply without re-applying an unchanged command every tick.
+    last_reported: Option<(u16, u16, u16)>,
+}
+
+static ANIM_STATE: Mutex<RefCell<Option<AnimState>>> = Mutex::new(RefCell::new(None));
+
+/// Arms the alarm for one tick at [`ANIMATION_TICK_HZ`] and unmasks its
+/// interrupt. Must be called exactly once, with `initial` matching whatever
+/// duty the PWM channels are already driving.
+pub fn init(mut alarm: Alarm0, initial: CustomHidCommand) {
+    let _ = alarm.schedule((1_000_000 / ANIMATION_TICK_HZ).micros());
+    alarm.enable_interrupt();
+
+    critical_section::with(|cs| {
+        ANIM_STATE.borrow(cs).replace(Some(AnimState {
+            wh: ChannelAnim::idle(initial.wh),
+            ir: ChannelAnim::idle(initial.ir),
+            uv: ChannelAnim::idle(initial.uv),
+            alarm,
+            last_reported: Some((initial.wh, initial.ir, initial.uv)),
+        }));
+    });
+
+    // Safety: TIMER0_IRQ_0 only touches ANIM_STATE, which is guarded by a
+    // critical section.
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::TIMER0_IRQ_0);
+    }
+}
+
+/// Snaps all three channels straight to `command`'s values, cancelling any
+/// ramp in progress. Used by the legacy HID/serial `SetAll` paths, which
+/// have no notion of a transition duration.
+pub fn set_instant(command: CustomHidCommand) {
+    critical_section::with(|cs| {
+        if let Some(state) = ANIM_STATE.borrow(cs).borrow_mut().as_mut() {
+            state.wh.set_instant(command.wh);
+            state.ir.set_instant(command.ir);
+            state.uv.set_instant(command.uv);
+        }
+    });
+}
+
+/// Starts a ramp of one channel to `target` over `duration_ms`, eased
+/// in/out rather than linear.
+pub fn start_ramp(channel: ChannelId, target: u16, duration_ms: u32) {
+    let duration_ticks = duration_ms * ANIMATION_TICK_HZ / 1000;
+    critical_section::with(|cs| {
+        if let Some(state) = ANIM_STATE.borrow(cs).borrow_mut().as_mut() {
+            let anim = match channel {
+                ChannelId::White => &mut state.wh,
+                ChannelId::Infrared => &mut state.ir,
+                ChannelId::Ultraviolet => &mut state.uv,
+            };
+            anim.start_ramp(target, duration_ticks, true);
+        }
+    });
+}
+
+/// Snaps whichever channels are `Some` straight to that duty, leaving `None`
+/// channels' ramp state untouched. Used by [`crate::regulate`] to drive
+/// channels under closed-loop regulation every ADC sample without
+/// interrupting a ramp in progress on a channel that's still open-loop.
+pub fn set_regulated(wh: Option<u16>, ir: Option<u16>, uv: Option<u16>) {
+    critical_section::with(|cs| {
+        if let Some(state) = ANIM_STATE.borrow(cs).borrow_mut().as_mut() {
+            if let Some(wh) = wh {
+                state.wh.set_instant(wh);
+            }
+            if let Some(ir) = ir {
+                state.ir.set_instant(ir);
+            }
+            if let Some(uv) = uv {
+                state.uv.set_instant(uv);
+            }
+        }
+    });
+}
+
+/// Returns the current interpolated duty values if they've changed since the
+/// last call, for the foreground task to apply to the PWM channels.
+pub fn take_if_changed() -> Option<(u16, u16, u16)> {
+    critical_section::with(|cs| {
+        let mut state = ANIM_STATE.borrow(cs).borrow_mut();
+        let state = state.as_mut()?;
+        let current = (state.wh.current(), state.ir.current(), state.uv.current());
+        if state.last_reported == Some(current) {
+            None
+        } else {
+            state.last_reported = Some(current);
+            Some(current)
+        }
+    })
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TIMER0_IRQ_0() {
+    critical_section::with(|cs| {
+        let mut state = ANIM_STATE.borrow(cs).borrow_mut();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        state.wh.tick();
+        state.ir.tick();
+        state.uv.tick();
+
+        let _ = state
+            .alarm
+            .schedule((1_000_000 / ANIMATION_TICK_HZ).micros());
+        state.alarm.clear_interrupt();
+    });
+}
@@ -48,38 +48,82 @@ pub const CUSTOM_HID_REPORT_DESCRIPTOR: &[u8] = &[
     0x95, 0x04,             //   REPORT_COUNT (4)
 	0x09, 0x01,			    //   USAGE (Vendor Usage 1)
     0x81, 0x02,             //   INPUT (Data,Var,Abs)
-    // Output Report: Host -> Device (4 bytes)
+    // Output Report: Host -> Device, 16-bit raw duty per channel (7 bytes).
+    // A legacy 4-byte, 8-bit-percent report (see `LegacyHidCommand`) is
+    // still accepted based on the received report length, for older hosts.
+    0x15, 0x00,             //   LOGICAL_MINIMUM (0)
+    0x27, 0xff, 0xff, 0x00, 0x00, //   LOGICAL_MAXIMUM (65535)
+    0x75, 0x10,             //   REPORT_SIZE (16)
+    0x95, 0x03,             //   REPORT_COUNT (3)
+	0x09, 0x01,			    //   USAGE (Vendor Usage 1)
+    0x91, 0x02,             //   OUTPUT (Data,Var,Abs)
     0x15, 0x00,             //   LOGICAL_MINIMUM (0)
     0x26, 0xff, 0x00,       //   LOGICAL_MAXIMUM (255)
     0x75, 0x08,             //   REPORT_SIZE (8)
-    0x95, 0x04,             //   REPORT_COUNT (4)
+    0x95, 0x01,             //   REPORT_COUNT (1)
 	0x09, 0x01,			    //   USAGE (Vendor Usage 1)
     0x91, 0x02,             //   OUTPUT (Data,Var,Abs)
-    0xc0                    // END_COLLECTION   
+    0xc0                    // END_COLLECTION
 ];
 
+/// `CustomHidReport::status` bit: the last received `CustomHidCommand` was
+/// applied to the PWM channels.
+pub const STATUS_APPLIED: u8 = 1 << 0;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Default, PackedStruct)]
 #[packed_struct(endian = "lsb")]
 pub struct CustomHidReport {
     #[packed_field]
-    pub data: u32,
+    pub wh: u8, // duty percent currently applied to the white led
+    #[packed_field]
+    pub ir: u8, // duty percent currently applied to the infrared led
+    #[packed_field]
+    pub uv: u8, // duty percent currently applied to the ultraviolet led
+    #[packed_field]
+    pub status: u8, // STATUS_* bitflags
 }
 
+/// A host command at full 16-bit resolution: each field is a raw duty
+/// value out of 0xffff, which the LED task scales to the PWM channel's
+/// actual `top` before calling `set_duty_cycle`.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Default, PackedStruct)]
 #[packed_struct(endian = "lsb")]
 pub struct CustomHidCommand {
     #[packed_field]
-    pub wh: u8, // duty percent for white led
+    pub wh: u16, // raw duty (out of 0xffff) for white led
     #[packed_field]
-    pub ir: u8, // duty percent for infrared led
+    pub ir: u16, // raw duty (out of 0xffff) for infrared led
     #[packed_field]
-    pub uv: u8, // duty percent for ultraviolet led
+    pub uv: u16, // raw duty (out of 0xffff) for ultraviolet led
     #[packed_field]
     pub reserved: u8,
 }
 
+/// The original 4-byte, 8-bit-percent report, still decoded when a host
+/// sends a report of this length so older hosts keep working.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default, PackedStruct)]
+#[packed_struct(endian = "lsb")]
+struct LegacyHidCommand {
+    #[packed_field]
+    wh: u8, // duty percent for white led
+    #[packed_field]
+    ir: u8, // duty percent for infrared led
+    #[packed_field]
+    uv: u8, // duty percent for ultraviolet led
+    #[packed_field]
+    reserved: u8,
+}
+
+/// Scales a legacy 0-100 percent value (clamped) to the 0-0xffff range used
+/// by `CustomHidCommand`.
+pub(crate) fn percent_to_duty(percent: u8) -> u16 {
+    const MAX_PERCENT: u32 = 100;
+    ((core::cmp::min(percent, 100) as u32 * u16::MAX as u32) / MAX_PERCENT) as u16
+}
+
 pub struct CustomHid<'a, B: UsbBus> {
     interface: Interface<'a, B, InBytes8, OutBytes8, ReportSingle>,
+    pending_report: Option<CustomHidReport>,
 }
 
 #[allow(dead_code)]
@@ -96,17 +140,37 @@ impl<B: UsbBus> CustomHid<'_, B> {
     }
 
     pub fn read_report(&mut self, command: &mut CustomHidCommand) -> Result<(), UsbHidError> {
-        let mut buf = [0u8; 4];
-        self.interface
+        let mut buf = [0u8; 7];
+        let len = self
+            .interface
             .read_report(&mut buf)
             .map_err(UsbHidError::from)?;
-        let cmd = CustomHidCommand::unpack(&buf).map_err(|_| {
-            error!("Error unpacking CustomHidCommand");
-            UsbHidError::SerializationError
-        })?;
-        *command = cmd;
+
+        *command = if len <= 4 {
+            let legacy = LegacyHidCommand::unpack(&buf[..4].try_into().unwrap()).map_err(|_| {
+                error!("Error unpacking legacy CustomHidCommand");
+                UsbHidError::SerializationError
+            })?;
+            CustomHidCommand {
+                wh: percent_to_duty(legacy.wh),
+                ir: percent_to_duty(legacy.ir),
+                uv: percent_to_duty(legacy.uv),
+                reserved: 0,
+            }
+        } else {
+            CustomHidCommand::unpack(&buf).map_err(|_| {
+                error!("Error unpacking CustomHidCommand");
+                UsbHidError::SerializationError
+            })?
+        };
         Ok(())
     }
+
+    /// Queues `report` to go out on the next call to [`tick`](Self::tick),
+    /// overwriting any report that hasn't been sent yet.
+    pub fn set_status(&mut self, report: CustomHidReport) {
+        self.pending_report = Some(report);
+    }
 }
 
 pub struct CustomHidConfig<'a> {
@@ -140,6 +204,7 @@ impl<'a, B: UsbBus + 'a> UsbAllocatable<'a, B> for CustomHidConfig<'a> {
     fn allocate(self, usb_alloc: &'a UsbBusAllocator<B>) -> Self::Allocated {
         CustomHid {
             interface: self.interface.allocate(usb_alloc),
+            pending_report: None,
         }
     }
 }
@@ -153,7 +218,13 @@ impl<'a, B: UsbBus> DeviceClass<'a> for CustomHid<'a, B> {
 
     fn reset(&mut self) {}
 
+    /// Flushes the status report queued by [`CustomHid::set_status`], if
+    /// any. Called from `USBCTRL_IRQ` on every bus poll, so reports reach
+    /// the host shortly after a command is applied.
     fn tick(&mut self) -> Result<(), UsbHidError> {
+        if let Some(report) = self.pending_report.take() {
+            self.write_report(&report)?;
+        }
         Ok(())
     }
 }
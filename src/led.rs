@@ -0,0 +1,84 @@
+//! led.rs
+//! Async LED driver task: consumes `CustomHidCommand`s relayed from the ramp
+//! engine and drives the three PWM channels accordingly.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use core::cell::RefCell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embedded_hal::pwm::SetDutyCycle;
+use rp235x_hal::pwm;
+
+use crate::hidcust::{CustomHidCommand, CustomHidReport, STATUS_APPLIED};
+use crate::usb_isr;
+
+/// Shared hand-off point between the animation relay task and the LED task: always holds
+/// the most recently received command, overwriting any command that hasn't
+/// been consumed yet.
+pub static LED_COMMAND: Signal<CriticalSectionRawMutex, CustomHidCommand> = Signal::new();
+
+/// Last duty-cycle percentages actually applied to the PWM channels, as
+/// `(wh, ir, uv)`. Read by the serial protocol task to answer
+/// `HostMessage::QueryStatus` without waiting on a fresh command.
+pub static LAST_DUTY: Mutex<CriticalSectionRawMutex, RefCell<(u8, u8, u8)>> =
+    Mutex::new(RefCell::new((25, 50, 75)));
+
+/// PWM channels driven by [`led_task`], grouped so they can be moved into the
+/// task as a single argument.
+pub struct LedChannels {
+    pub wh: pwm::Channel<pwm::Pwm2, pwm::FA>,
+    pub ir: pwm::Channel<pwm::Pwm1, pwm::FB>,
+    pub uv: pwm::Channel<pwm::Pwm2, pwm::FB>,
+}
+
+/// Scales a raw 16-bit duty value (out of 0xffff, as carried by
+/// `CustomHidCommand`) to a channel's actual compare range.
+pub(crate) fn scale_to_channel_max(value: u16, channel_max: u16) -> u16 {
+    ((value as u32 * channel_max as u32) / u16::MAX as u32) as u16
+}
+
+/// Converts a raw 16-bit duty value to the 0-100 percent used by
+/// `CustomHidReport` and [`LAST_DUTY`].
+pub(crate) fn duty_to_percent(value: u16) -> u8 {
+    ((value as u32 * 100 + u16::MAX as u32 / 2) / u16::MAX as u32) as u8
+}
+
+/// Awaits commands pushed onto [`LED_COMMAND`] and applies them to the PWM
+/// channels, scaling each 16-bit value to the channel's compare range.
+#[embassy_executor::task]
+pub async fn led_task(mut channels: LedChannels) {
+    loop {
+        let command = LED_COMMAND.wait().await;
+        info!(
+            "Applying command: wh={} ir={} uv={}",
+            command.wh, command.ir, command.uv
+        );
+
+        channels
+            .ir
+            .set_duty_cycle(scale_to_channel_max(command.ir, channels.ir.max_duty_cycle()))
+            .unwrap();
+        channels
+            .wh
+            .set_duty_cycle(scale_to_channel_max(command.wh, channels.wh.max_duty_cycle()))
+            .unwrap();
+        channels
+            .uv
+            .set_duty_cycle(scale_to_channel_max(command.uv, channels.uv.max_duty_cycle()))
+            .unwrap();
+
+        let wh = duty_to_percent(command.wh);
+        let ir = duty_to_percent(command.ir);
+        let uv = duty_to_percent(command.uv);
+        LAST_DUTY.lock(|duty| *duty.borrow_mut() = (wh, ir, uv));
+        usb_isr::set_status(CustomHidReport {
+            wh,
+            ir,
+            uv,
+            status: STATUS_APPLIED,
+        });
+    }
+}
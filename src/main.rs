@@ -1,22 +1,48 @@
 //! Blinks the LED on a Pico board
 //!
 //! This will blink an LED attached to GP25, which is the pin the Pico uses for the on-board LED.
-#![no_std]
-#![no_main]
+// Pure logic modules (`persist`, `regulate`) get `#[cfg(test)]` unit tests
+// that run on the host, so std/the normal entry point stay available for
+// `cargo test` and only the on-device build is `no_std`/`no_main`.
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 use core::cmp::min;
 
 use defmt::*;
 use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_time::{Duration, Timer};
 use embedded_hal::pwm::SetDutyCycle;
 use panic_probe as _;
 use rp235x_hal::clocks::init_clocks_and_plls;
-use rp235x_hal::{self as hal, entry, pac, Clock};
+use rp235x_hal::{self as hal, pac, Clock};
 use usb_device::bus::UsbBusAllocator;
 use usb_device::device::{StringDescriptors, UsbDeviceBuilder, UsbVidPid};
-use usbd_human_interface_device::{prelude::UsbHidClassBuilder, UsbHidError};
+use usbd_human_interface_device::prelude::UsbHidClassBuilder;
 
+mod adc;
+mod animation;
 mod hidcust;
+mod led;
+mod persist;
+mod protocol;
+mod regulate;
+mod serial;
+mod usb_isr;
+
+use led::{LedChannels, LED_COMMAND};
+use persist::PERSIST_COMMAND;
+
+/// How often the foreground relay checks for a new command out of the
+/// `USBCTRL_IRQ`-owned shared cell. USB servicing itself is fully
+/// interrupt-driven and is not affected by this cadence.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the foreground task polls the ramp engine for a new
+/// interpolated value. Well under the engine's own 1kHz tick so ramps still
+/// look smooth on the PWM outputs.
+const ANIMATION_POLL_INTERVAL: Duration = Duration::from_millis(5);
 
 // Provide an alias for our BSP so we can switch targets quickly.
 // Uncomment the BSP you included in Cargo.toml, the rest of the code does not need to change.
@@ -27,11 +53,46 @@ mod hidcust;
 #[used]
 pub static IMAGE_DEF: hal::block::ImageDef = hal::block::ImageDef::secure_exe();
 
-#[entry]
-fn main() -> ! {
+/// Relays commands decoded by `USBCTRL_IRQ` out of [`usb_isr`]'s shared cell
+/// into the ramp engine. USB enumeration and report transfer all happen in
+/// the interrupt handler, so this task only ever touches the one-slot
+/// command cell and never blocks USB servicing. The legacy HID report has no
+/// notion of a transition duration, so these commands snap instantly.
+#[embassy_executor::task]
+async fn command_relay_task() -> ! {
+    loop {
+        if let Some(command) = usb_isr::take_latest_command() {
+            animation::set_instant(command);
+        }
+        Timer::after(COMMAND_POLL_INTERVAL).await;
+    }
+}
+
+/// Polls the ramp engine for interpolated duty values and forwards any
+/// change onto [`LED_COMMAND`] (and, transitively, flash persistence),
+/// turning `StartRamp` requests and instant `set_instant` snaps alike into
+/// the same PWM/status/flash pipeline a legacy command uses.
+#[embassy_executor::task]
+async fn animation_relay_task() -> ! {
+    loop {
+        if let Some((wh, ir, uv)) = animation::take_if_changed() {
+            let command = hidcust::CustomHidCommand {
+                wh,
+                ir,
+                uv,
+                reserved: 0,
+            };
+            LED_COMMAND.signal(command);
+            PERSIST_COMMAND.signal(command);
+        }
+        Timer::after(ANIMATION_POLL_INTERVAL).await;
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
     info!("Program start");
     let mut pac = pac::Peripherals::take().unwrap();
-    //let core = cortex_m::Peripherals::take().unwrap();
     let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
     let sio = hal::Sio::new(pac.SIO);
 
@@ -49,8 +110,6 @@ fn main() -> ! {
     .ok()
     .unwrap();
 
-    //let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
-
     // GPIO
     let pins = hal::gpio::Pins::new(
         pac.IO_BANK0,
@@ -60,26 +119,34 @@ fn main() -> ! {
     );
 
     // USB HID
-    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+    static USB_BUS: static_cell::StaticCell<UsbBusAllocator<hal::usb::UsbBus>> =
+        static_cell::StaticCell::new();
+    let usb_bus = USB_BUS.init(UsbBusAllocator::new(hal::usb::UsbBus::new(
         pac.USB,
         pac.USB_DPRAM,
         clocks.usb_clock,
         true,
         &mut pac.RESETS,
-    ));
+    )));
 
-    let mut hid = UsbHidClassBuilder::new()
+    let hid = UsbHidClassBuilder::new()
         .add_device(hidcust::CustomHidConfig::default())
-        .build(&usb_bus);
+        .build(usb_bus);
+    let serial = usbd_serial::SerialPort::new(usb_bus);
 
-    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1209, 0x0001))
+    let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1209, 0x0001))
         .strings(&[StringDescriptors::default()
             .manufacturer("Panasonic Corporation")
             .product("Laundry LED Controller")
             .serial_number("TEST")])
         .unwrap()
+        .composite_with_iads()
         .build();
 
+    // Hand the devices/classes off to USBCTRL_IRQ: from here on, USB
+    // servicing happens entirely in the interrupt handler.
+    usb_isr::init(usb_dev, hid, serial);
+
     // Initialize PWM for LED control
     //   freq = sysclk(150MHz) / ((top + 1) * div)
     let mut pwm_slices = hal::pwm::Slices::new(pac.PWM, &mut pac.RESETS);
@@ -87,6 +154,26 @@ fn main() -> ! {
     let freq = clocks.system_clock.freq().to_Hz(); // Target 1kHz PWM frequency
     let top = min((freq / (pwm_freq_khz * 1000)) / 2 - 1, 65535) as u16;
 
+    // Restore the last command written to flash, if any, falling back to
+    // these defaults (25%/50%/75%) on first boot or if the stored record is
+    // invalid. Only raw duty is restored; regulation mode/setpoint/gains
+    // (see `persist::load`) do not survive a reboot and default back to
+    // `RegulationMode::OpenLoop`.
+    let restored = persist::load();
+    let initial = restored.unwrap_or(hidcust::CustomHidCommand {
+        wh: hidcust::percent_to_duty(25),
+        ir: hidcust::percent_to_duty(50),
+        uv: hidcust::percent_to_duty(75),
+        reserved: 0,
+    });
+    led::LAST_DUTY.lock(|duty| {
+        *duty.borrow_mut() = (
+            led::duty_to_percent(initial.wh),
+            led::duty_to_percent(initial.ir),
+            led::duty_to_percent(initial.uv),
+        )
+    });
+
     // PWM1B: GPIO3
     let pwm1 = &mut pwm_slices.pwm1;
     pwm1.set_ph_correct();
@@ -94,9 +181,11 @@ fn main() -> ! {
     pwm1.set_div_int(1);
     pwm1.set_div_frac(0);
     pwm1.enable();
-    let led_ir = &mut pwm1.channel_b;
+    let mut led_ir = pwm1.channel_b;
     led_ir.output_to(pins.gpio3);
-    led_ir.set_duty_cycle_percent(50).unwrap(); // Start with LED off
+    led_ir
+        .set_duty_cycle(led::scale_to_channel_max(initial.ir, led_ir.max_duty_cycle()))
+        .unwrap();
 
     // PWM2A: GPIO4, PWM2B: GPIO5
     let pwm2 = &mut pwm_slices.pwm2;
@@ -105,39 +194,41 @@ fn main() -> ! {
     pwm2.set_div_int(1);
     pwm2.set_div_frac(0);
     pwm2.enable();
-    let led_wh = &mut pwm2.channel_a;
+    let mut led_wh = pwm2.channel_a;
     led_wh.output_to(pins.gpio4);
-    led_wh.set_duty_cycle_percent(25).unwrap(); // Start with LED off
-    let led_uv = &mut pwm2.channel_b;
+    led_wh
+        .set_duty_cycle(led::scale_to_channel_max(initial.wh, led_wh.max_duty_cycle()))
+        .unwrap();
+    let mut led_uv = pwm2.channel_b;
     led_uv.output_to(pins.gpio5);
-    led_uv.set_duty_cycle_percent(75).unwrap(); // Start with LED off
-
-    loop {
-        let mut command = hidcust::CustomHidCommand::default();
-        match hid.device().read_report(&mut command) {
-            Ok(()) => {
-                info!(
-                    "Received command: wh={} ir={} uv={}",
-                    command.wh, command.ir, command.uv
-                );
-
-                led_ir.set_duty_cycle_percent(min(command.ir, 100)).unwrap();
-                led_wh.set_duty_cycle_percent(min(command.wh, 100)).unwrap();
-                led_uv.set_duty_cycle_percent(min(command.uv, 100)).unwrap();
-            }
-            Err(UsbHidError::WouldBlock) => {
-                // No data available, do nothing
-            }
-            Err(UsbHidError::SerializationError) => {
-                info!("Serialization error in received command");
-            }
-            Err(_) => {
-                info!("Unknown error in received command");
-            }
-        }
-
-        if usb_dev.poll(&mut [&mut hid]) {}
-    }
+    led_uv
+        .set_duty_cycle(led::scale_to_channel_max(initial.uv, led_uv.max_duty_cycle()))
+        .unwrap();
+
+    // Ramp engine: ticks on a hardware alarm, starting from the same duty
+    // values the PWM channels were just set to.
+    let timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+    let alarm0 = timer.alarm_0().unwrap();
+    animation::init(alarm0, initial);
+
+    // Photodiode feedback for closed-loop regulation, on the RP2350's first
+    // ADC-capable GPIO. Channels default to open-loop, so this only matters
+    // once a host sends a `HostMessage::SetRegulation`.
+    let adc = hal::Adc::new(pac.ADC, &mut pac.RESETS);
+    let photodiode_pin = hal::adc::AdcPin::new(pins.gpio26.into_floating_input()).unwrap();
+
+    spawner
+        .spawn(led::led_task(LedChannels {
+            wh: led_wh,
+            ir: led_ir,
+            uv: led_uv,
+        }))
+        .unwrap();
+    spawner.spawn(command_relay_task()).unwrap();
+    spawner.spawn(animation_relay_task()).unwrap();
+    spawner.spawn(persist::persist_task()).unwrap();
+    spawner.spawn(serial::serial_task()).unwrap();
+    spawner.spawn(adc::adc_task(adc, photodiode_pin)).unwrap();
 }
 
 /// Program metadata for `picotool info`
@@ -0,0 +1,227 @@
+//! persist.rs
+//! Flash-backed persistence for the last LED duty-cycle command, so the
+//! board resumes its previous brightness after a power loss instead of
+//! falling back to the hard-coded defaults.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use packed_struct::prelude::*;
+use rp2040_flash::flash;
+
+use crate::hidcust::CustomHidCommand;
+
+/// Every command the LED task applies is also pushed here; [`persist_task`]
+/// debounces and commits it to flash.
+pub static PERSIST_COMMAND: Signal<CriticalSectionRawMutex, CustomHidCommand> = Signal::new();
+
+/// Marks a record as ours (also used to detect blank/erased flash, which
+/// reads back as `0xff` bytes and therefore never matches).
+const MAGIC: u32 = 0x4C45_4421; // "LED!"
+
+/// Last sector of a 2 MiB flash part, measured from the start of flash
+/// (`XIP_BASE`). Firmware images on this board are well under 2 MiB, so this
+/// sector never overlaps the program.
+const FLASH_TARGET_OFFSET: u32 = 0x1F_F000;
+const XIP_BASE: u32 = 0x1000_0000;
+const RECORD_LEN: usize = 13;
+
+/// Only commit to flash once a command has been stable for this long, so a
+/// host streaming updates quickly doesn't wear out the sector.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default, PackedStruct)]
+#[packed_struct(endian = "lsb")]
+struct PersistedRecord {
+    #[packed_field]
+    magic: u32,
+    #[packed_field]
+    wh: u16, // raw duty (out of 0xffff) for white led
+    #[packed_field]
+    ir: u16, // raw duty (out of 0xffff) for infrared led
+    #[packed_field]
+    uv: u16, // raw duty (out of 0xffff) for ultraviolet led
+    #[packed_field]
+    reserved: u8,
+    #[packed_field]
+    crc: u16,
+}
+
+impl PersistedRecord {
+    fn new(command: CustomHidCommand) -> Self {
+        let mut record = Self {
+            magic: MAGIC,
+            wh: command.wh,
+            ir: command.ir,
+            uv: command.uv,
+            reserved: 0,
+            crc: 0,
+        };
+        record.crc = record.checksum();
+        record
+    }
+
+    fn checksum(&self) -> u16 {
+        let [wh_lo, wh_hi] = self.wh.to_le_bytes();
+        let [ir_lo, ir_hi] = self.ir.to_le_bytes();
+        let [uv_lo, uv_hi] = self.uv.to_le_bytes();
+        crc16(&[
+            wh_lo, wh_hi, ir_lo, ir_hi, uv_lo, uv_hi, self.reserved,
+        ])
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.crc == self.checksum()
+    }
+
+    fn into_command(self) -> CustomHidCommand {
+        CustomHidCommand {
+            wh: self.wh,
+            ir: self.ir,
+            uv: self.uv,
+            reserved: 0,
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE, computed in software since the RP2350's hardware CRC
+/// block isn't wired up here yet and the record is tiny.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Reads the persisted command out of flash, returning `None` if the sector
+/// is blank or the stored record fails its magic/CRC check.
+///
+/// Only carries raw `wh`/`ir`/`uv` duty — a `regulate::RegulationMode`,
+/// setpoint and PID gains a host configured via `HostMessage::SetRegulation`
+/// are never written here, so a regulated channel's last PID output gets
+/// restored as a plain open-loop duty on the next boot and the regulation
+/// config itself does not survive a reboot. A host relying on regulation
+/// across power cycles needs to resend `SetRegulation` after boot.
+pub fn load() -> Option<CustomHidCommand> {
+    // Safety: FLASH_TARGET_OFFSET is within the flash's XIP-mapped region and
+    // we only read RECORD_LEN bytes, well inside the reserved sector.
+    let bytes = unsafe {
+        core::slice::from_raw_parts((XIP_BASE + FLASH_TARGET_OFFSET) as *const u8, RECORD_LEN)
+    };
+    let mut buf = [0u8; RECORD_LEN];
+    buf.copy_from_slice(bytes);
+
+    match PersistedRecord::unpack(&buf) {
+        Ok(record) if record.is_valid() => {
+            info!(
+                "Restored persisted command: wh={} ir={} uv={}",
+                record.wh, record.ir, record.uv
+            );
+            Some(record.into_command())
+        }
+        _ => {
+            info!("No valid persisted command in flash, using defaults");
+            None
+        }
+    }
+}
+
+/// Erases the reserved sector and writes `command` as the new record.
+fn commit(command: CustomHidCommand) {
+    let record = PersistedRecord::new(command);
+    let Ok(packed) = record.pack() else {
+        warn!("Failed to pack persisted command, skipping flash write");
+        return;
+    };
+    let mut page = [0xffu8; 256];
+    page[..RECORD_LEN].copy_from_slice(&packed);
+
+    // Safety: both cores must stay out of flash for the duration of the
+    // erase/program pair. `critical_section` suffices here because this is a
+    // single-core application; a multicore build would also need the second
+    // core parked (e.g. via `flash::flash_range_erase_and_program`'s
+    // multicore-safe variant) before calling these.
+    critical_section::with(|_cs| unsafe {
+        flash::flash_range_erase(FLASH_TARGET_OFFSET, 4096, true);
+        flash::flash_range_program(FLASH_TARGET_OFFSET, &page, true);
+    });
+    info!(
+        "Persisted command to flash: wh={} ir={} uv={}",
+        command.wh, command.ir, command.uv
+    );
+}
+
+/// Awaits commands on [`PERSIST_COMMAND`] and commits the latest one once it
+/// has been stable for [`DEBOUNCE`], coalescing any commands that arrive in
+/// the meantime into a single flash write.
+#[embassy_executor::task]
+pub async fn persist_task() -> ! {
+    loop {
+        let mut pending = PERSIST_COMMAND.wait().await;
+        loop {
+            match embassy_time::with_timeout(DEBOUNCE, PERSIST_COMMAND.wait()).await {
+                Ok(newer) => pending = newer,
+                Err(_timeout) => break,
+            }
+        }
+        commit(pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_matches_known_test_vector() {
+        // CRC-16/CCITT-FALSE("123456789") == 0x29B1, the standard check
+        // value for this polynomial/init combination.
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn record_round_trips_through_pack_and_unpack() {
+        let command = CustomHidCommand {
+            wh: 0x1234,
+            ir: 0x5678,
+            uv: 0x9abc,
+            reserved: 0,
+        };
+        let record = PersistedRecord::new(command);
+        assert!(record.is_valid());
+
+        let packed = record.pack().unwrap();
+        let unpacked = PersistedRecord::unpack(&packed).unwrap();
+        assert!(unpacked.is_valid());
+        assert_eq!(unpacked.into_command(), command);
+    }
+
+    #[test]
+    fn corrupted_record_fails_validation() {
+        let mut record = PersistedRecord::new(CustomHidCommand {
+            wh: 0x1234,
+            ir: 0x5678,
+            uv: 0x9abc,
+            reserved: 0,
+        });
+        record.wh ^= 0xff;
+        assert!(!record.is_valid());
+    }
+
+    #[test]
+    fn blank_flash_bytes_do_not_pass_as_a_record() {
+        let blank = [0xffu8; RECORD_LEN];
+        let record = PersistedRecord::unpack(&blank).unwrap();
+        assert!(!record.is_valid());
+    }
+}
@@ -0,0 +1,72 @@
+//! protocol.rs
+//! Structured command protocol carried over the CDC-ACM serial interface.
+//! Messages are serialized with `postcard` and framed with COBS, giving
+//! hosts an extensible alternative to the fixed-layout HID report: new
+//! message kinds can be added as new enum variants. Frames carry no version
+//! field, and postcard's encoding is positional, so adding fields to an
+//! existing variant changes its wire format.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use serde::{Deserialize, Serialize};
+
+/// Largest COBS-framed, postcard-encoded frame we exchange in either
+/// direction. Generous enough for any `HostMessage`/`DeviceMessage` variant
+/// plus COBS overhead and the trailing zero delimiter.
+pub const MAX_FRAME_LEN: usize = 32;
+
+/// Which LED channel a message targets.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ChannelId {
+    White,
+    Infrared,
+    Ultraviolet,
+}
+
+/// Whether a channel's duty is set directly by the host (the default) or
+/// held at a setpoint by [`crate::regulate`] using photodiode feedback.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RegulationMode {
+    OpenLoop,
+    Regulated,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum HostMessage {
+    /// Set a single channel's duty cycle, as a percent (0-100).
+    SetChannel { id: ChannelId, duty: u8 },
+    /// Set all three channels' duty cycles at once, as percentages.
+    SetAll { wh: u8, ir: u8, uv: u8 },
+    /// Request a timed fade of one channel to `target`, over `duration_ms`.
+    /// Handled by `crate::animation`, which interpolates duty from its
+    /// current value to `target` over the requested duration; the device
+    /// acks immediately and the fade proceeds in the background.
+    StartRamp {
+        id: ChannelId,
+        target: u8,
+        duration_ms: u16,
+    },
+    /// Ask the device to reply with a `DeviceMessage::Status`.
+    QueryStatus,
+    /// Switch a channel between open-loop duty control and closed-loop
+    /// photodiode regulation, and (for `Regulated`) set its setpoint and PID
+    /// gains. Gains are Q8.8 fixed-point. Channels default to `OpenLoop` on
+    /// boot, so hosts that never send this message see unchanged behavior.
+    SetRegulation {
+        id: ChannelId,
+        mode: RegulationMode,
+        setpoint: u8,
+        kp: i16,
+        ki: i16,
+        kd: i16,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DeviceMessage {
+    /// Current duty-cycle percentages, sent in reply to `QueryStatus`.
+    Status { wh: u8, ir: u8, uv: u8 },
+    /// A `HostMessage` was decoded and applied.
+    Ack,
+    /// A frame was received but could not be decoded or applied.
+    Error,
+}
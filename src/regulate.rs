@@ -0,0 +1,214 @@
+//! regulate.rs
+//! Closed-loop brightness regulation: a photodiode reading sampled by
+//! `adc` feeds a per-channel PID controller that holds a commanded
+//! setpoint, as an alternative to a host setting duty directly. Channels
+//! default to `RegulationMode::OpenLoop`, so a host that never sends
+//! `HostMessage::SetRegulation` sees unchanged open-loop behavior.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use crate::animation;
+use crate::hidcust::percent_to_duty;
+use crate::protocol::{ChannelId, RegulationMode};
+
+/// Clamp on the integral term, in the same Q8.8 units as the `ki * integral`
+/// product, to keep a channel that's far from its setpoint (e.g. with the
+/// photodiode covered) from winding up an enormous integral that then
+/// overshoots for a long time once the obstruction clears.
+const INTEGRAL_LIMIT: i32 = 0x00FF_0000;
+
+/// A single channel's PID loop, operating directly in 16-bit duty units
+/// (0..=0xffff, the same scale as `CustomHidCommand` and the photodiode
+/// measurement fed in by `adc`). Gains are Q8.8 fixed-point.
+#[derive(Clone, Copy)]
+struct Pid {
+    kp: i32,
+    ki: i32,
+    kd: i32,
+    integral: i32,
+    prev_measurement: i32,
+}
+
+impl Pid {
+    const fn new() -> Self {
+        Self {
+            kp: 0,
+            ki: 0,
+            kd: 0,
+            integral: 0,
+            prev_measurement: 0,
+        }
+    }
+
+    /// Runs one step and returns the duty to apply, clamped to
+    /// `0..=max_output`. Derivative is computed on the measurement rather
+    /// than the error, so a setpoint change doesn't itself cause a
+    /// derivative kick.
+    fn step(&mut self, setpoint: i32, measurement: i32, max_output: i32) -> u16 {
+        let error = setpoint - measurement;
+        self.integral = ((self.integral as i64 + error as i64 * self.ki as i64)
+            .clamp(-INTEGRAL_LIMIT as i64, INTEGRAL_LIMIT as i64)) as i32;
+        let derivative = measurement - self.prev_measurement;
+        self.prev_measurement = measurement;
+
+        // Accumulate in i64: kp/ki/kd are full-range i16 and error/derivative
+        // can reach +-65535, so the i32 products alone can approach i32::MAX
+        // before the integral term or the final >>8 are even applied.
+        let output = (self.kp as i64 * error as i64 + self.integral as i64
+            - self.kd as i64 * derivative as i64)
+            >> 8;
+        output.clamp(0, max_output as i64) as u16
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ChannelRegulation {
+    mode: RegulationMode,
+    setpoint: u16,
+    pid: Pid,
+}
+
+impl ChannelRegulation {
+    const fn open_loop() -> Self {
+        Self {
+            mode: RegulationMode::OpenLoop,
+            setpoint: 0,
+            pid: Pid::new(),
+        }
+    }
+}
+
+struct RegulateState {
+    wh: ChannelRegulation,
+    ir: ChannelRegulation,
+    uv: ChannelRegulation,
+}
+
+static STATE: Mutex<RefCell<RegulateState>> = Mutex::new(RefCell::new(RegulateState {
+    wh: ChannelRegulation::open_loop(),
+    ir: ChannelRegulation::open_loop(),
+    uv: ChannelRegulation::open_loop(),
+}));
+
+fn channel_mut(state: &mut RegulateState, id: ChannelId) -> &mut ChannelRegulation {
+    match id {
+        ChannelId::White => &mut state.wh,
+        ChannelId::Infrared => &mut state.ir,
+        ChannelId::Ultraviolet => &mut state.uv,
+    }
+}
+
+/// Sets a channel's mode, setpoint (0-100%) and PID gains, as requested by
+/// `HostMessage::SetRegulation`. Switching to `Regulated` resets the PID's
+/// integral and derivative history so gains changed mid-flight don't act on
+/// stale state.
+pub fn configure(id: ChannelId, mode: RegulationMode, setpoint_percent: u8, kp: i16, ki: i16, kd: i16) {
+    critical_section::with(|cs| {
+        let mut state = STATE.borrow(cs).borrow_mut();
+        let channel = channel_mut(&mut state, id);
+        channel.mode = mode;
+        channel.setpoint = percent_to_duty(setpoint_percent);
+        channel.pid = Pid {
+            kp: kp as i32,
+            ki: ki as i32,
+            kd: kd as i32,
+            integral: 0,
+            prev_measurement: 0,
+        };
+    });
+}
+
+/// Runs one PID step for every channel currently in `Regulated` mode against
+/// the latest photodiode `measurement` (0..=0xffff), and applies the result
+/// to the PWM outputs. Channels still in `OpenLoop` mode are left alone
+/// entirely, so a ramp in progress on one channel isn't disturbed by
+/// regulation running on another.
+pub fn on_measurement(measurement: u16) {
+    let (wh, ir, uv) = critical_section::with(|cs| {
+        let mut state = STATE.borrow(cs).borrow_mut();
+        (
+            regulate_channel(&mut state.wh, measurement),
+            regulate_channel(&mut state.ir, measurement),
+            regulate_channel(&mut state.uv, measurement),
+        )
+    });
+
+    if wh.is_some() || ir.is_some() || uv.is_some() {
+        animation::set_regulated(wh, ir, uv);
+    }
+}
+
+/// Returns `None` unless `channel` is `Regulated`, in which case it runs the
+/// channel's PID step and returns the duty to apply.
+fn regulate_channel(channel: &mut ChannelRegulation, measurement: u16) -> Option<u16> {
+    match channel.mode {
+        RegulationMode::OpenLoop => None,
+        RegulationMode::Regulated => Some(channel.pid.step(
+            channel.setpoint as i32,
+            measurement as i32,
+            u16::MAX as i32,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_clamps_output_to_max() {
+        let mut pid = Pid {
+            kp: 0x0100, // 1.0 in Q8.8
+            ki: 0,
+            kd: 0,
+            integral: 0,
+            prev_measurement: 0,
+        };
+        assert_eq!(pid.step(0xffff, 0, u16::MAX as i32), u16::MAX);
+    }
+
+    #[test]
+    fn step_clamps_output_to_zero() {
+        let mut pid = Pid {
+            kp: 0x0100,
+            ki: 0,
+            kd: 0,
+            integral: 0,
+            prev_measurement: 0,
+        };
+        assert_eq!(pid.step(0, 0xffff, u16::MAX as i32), 0);
+    }
+
+    #[test]
+    fn step_does_not_overflow_at_full_gain_and_error_range() {
+        // Worst case the protocol permits: full-range i16 gains against a
+        // full-range u16 error (measurement=0, setpoint=u16::MAX).
+        let mut pid = Pid {
+            kp: i16::MAX as i32,
+            ki: i16::MAX as i32,
+            kd: i16::MAX as i32,
+            integral: 0,
+            prev_measurement: 0,
+        };
+        assert_eq!(pid.step(u16::MAX as i32, 0, u16::MAX as i32), u16::MAX);
+    }
+
+    #[test]
+    fn integral_clamps_to_limit_instead_of_winding_up_unbounded() {
+        let mut pid = Pid {
+            kp: 0,
+            ki: i16::MAX as i32,
+            kd: 0,
+            integral: 0,
+            prev_measurement: 0,
+        };
+        for _ in 0..10 {
+            pid.step(u16::MAX as i32, 0, u16::MAX as i32);
+        }
+        assert!(pid.integral <= INTEGRAL_LIMIT);
+        assert!(pid.integral >= -INTEGRAL_LIMIT);
+    }
+}
@@ -0,0 +1,81 @@
+//! serial.rs
+//! Dispatches `HostMessage`s decoded off the CDC-ACM serial port (see
+//! `usb_isr`) onto the same LED/persistence pipeline the legacy HID path
+//! uses, and answers status queries.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use embassy_time::{Duration, Timer};
+
+use crate::animation;
+use crate::hidcust::{percent_to_duty, CustomHidCommand};
+use crate::led::LAST_DUTY;
+use crate::protocol::{ChannelId, DeviceMessage, HostMessage};
+use crate::regulate;
+use crate::usb_isr;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Awaits `HostMessage`s relayed out of `usb_isr`'s shared cell and applies
+/// them the same way a legacy HID command would, replying over the serial
+/// port where the protocol calls for it.
+#[embassy_executor::task]
+pub async fn serial_task() -> ! {
+    loop {
+        if let Some(message) = usb_isr::take_latest_message() {
+            match message {
+                HostMessage::SetChannel { id, duty } => {
+                    let (mut wh, mut ir, mut uv) = LAST_DUTY.lock(|duty| *duty.borrow());
+                    match id {
+                        ChannelId::White => wh = duty,
+                        ChannelId::Infrared => ir = duty,
+                        ChannelId::Ultraviolet => uv = duty,
+                    }
+                    apply(wh, ir, uv);
+                    usb_isr::write_serial_frame(&DeviceMessage::Ack);
+                }
+                HostMessage::SetAll { wh, ir, uv } => {
+                    apply(wh, ir, uv);
+                    usb_isr::write_serial_frame(&DeviceMessage::Ack);
+                }
+                HostMessage::StartRamp {
+                    id,
+                    target,
+                    duration_ms,
+                } => {
+                    animation::start_ramp(id, percent_to_duty(target), duration_ms as u32);
+                    usb_isr::write_serial_frame(&DeviceMessage::Ack);
+                }
+                HostMessage::QueryStatus => {
+                    let (wh, ir, uv) = LAST_DUTY.lock(|duty| *duty.borrow());
+                    usb_isr::write_serial_frame(&DeviceMessage::Status { wh, ir, uv });
+                }
+                HostMessage::SetRegulation {
+                    id,
+                    mode,
+                    setpoint,
+                    kp,
+                    ki,
+                    kd,
+                } => {
+                    regulate::configure(id, mode, setpoint, kp, ki, kd);
+                    usb_isr::write_serial_frame(&DeviceMessage::Ack);
+                }
+            }
+        }
+
+        Timer::after(POLL_INTERVAL).await;
+    }
+}
+
+/// Snaps to a command given as 0-100 percentages (as carried by the serial
+/// protocol), scaling each to the 16-bit resolution `CustomHidCommand` uses.
+/// Routed through the ramp engine like the legacy HID path, so it cancels
+/// any ramp in progress and keeps the engine's notion of "current" in sync.
+fn apply(wh: u8, ir: u8, uv: u8) {
+    animation::set_instant(CustomHidCommand {
+        wh: percent_to_duty(wh),
+        ir: percent_to_duty(ir),
+        uv: percent_to_duty(uv),
+        reserved: 0,
+    });
+}
@@ -0,0 +1,163 @@
+//! usb_isr.rs
+//! Interrupt-driven USB servicing: enumeration and HID report transfer run in
+//! the `USBCTRL_IRQ` handler so they stay responsive regardless of what the
+//! foreground (LED) work is doing, following the rp-hal
+//! `pico_usb_serial_interrupt` pattern.
+//! Copyright 2025 Panasonic Corporation. All rights reserved.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use defmt::info;
+use heapless::Vec;
+use rp235x_hal::pac::{self, interrupt};
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::UsbDevice;
+use usbd_human_interface_device::{device::DeviceClass, usb_class::UsbHidClass, UsbHidError};
+use usbd_serial::SerialPort;
+
+use crate::hidcust::{CustomHid, CustomHidCommand, CustomHidReport};
+use crate::protocol::{HostMessage, MAX_FRAME_LEN};
+
+type Hid<'a> = UsbHidClass<'a, rp235x_hal::usb::UsbBus, (CustomHid<'a, rp235x_hal::usb::UsbBus>,)>;
+type Serial<'a> = SerialPort<'a, rp235x_hal::usb::UsbBus>;
+
+/// Everything `USBCTRL_IRQ` needs to poll the bus and decode reports, plus
+/// the latest decoded command/message for the foreground tasks to pick up.
+struct UsbContext<'a> {
+    device: UsbDevice<'a, rp235x_hal::usb::UsbBus>,
+    hid: Hid<'a>,
+    serial: Serial<'a>,
+    latest_command: Option<CustomHidCommand>,
+    latest_message: Option<HostMessage>,
+    /// COBS frame bytes received so far, up to and not including the next
+    /// zero delimiter.
+    rx_buf: Vec<u8, MAX_FRAME_LEN>,
+}
+
+static USB_CONTEXT: Mutex<RefCell<Option<UsbContext>>> = Mutex::new(RefCell::new(None));
+
+/// Moves the USB device/classes into the shared cell and unmasks the
+/// interrupt. Must be called exactly once, after the bus, device and classes
+/// have been built, and before any code relies on `take_latest_command` /
+/// `take_latest_message`.
+pub fn init(device: UsbDevice<'static, rp235x_hal::usb::UsbBus>, hid: Hid<'static>, serial: Serial<'static>) {
+    critical_section::with(|cs| {
+        USB_CONTEXT.borrow(cs).replace(Some(UsbContext {
+            device,
+            hid,
+            serial,
+            latest_command: None,
+            latest_message: None,
+            rx_buf: Vec::new(),
+        }));
+    });
+
+    // Safety: USBCTRL_IRQ only touches USB_CONTEXT, which is guarded by a
+    // critical section, and the interrupt priority is left at its default.
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::USBCTRL_IRQ);
+    }
+}
+
+/// Takes the most recently decoded command, if one has arrived since the
+/// last call, without blocking on the interrupt.
+pub fn take_latest_command() -> Option<CustomHidCommand> {
+    critical_section::with(|cs| {
+        USB_CONTEXT
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .and_then(|ctx| ctx.latest_command.take())
+    })
+}
+
+/// Queues a device-to-host status report; it goes out the next time
+/// `USBCTRL_IRQ` fires and calls [`CustomHid::tick`].
+pub fn set_status(report: CustomHidReport) {
+    critical_section::with(|cs| {
+        if let Some(ctx) = USB_CONTEXT.borrow(cs).borrow_mut().as_mut() {
+            ctx.hid.device().set_status(report);
+        }
+    });
+}
+
+/// Takes the most recently decoded `HostMessage` from the CDC-ACM serial
+/// port, if a full frame has arrived since the last call.
+pub fn take_latest_message() -> Option<HostMessage> {
+    critical_section::with(|cs| {
+        USB_CONTEXT
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .and_then(|ctx| ctx.latest_message.take())
+    })
+}
+
+/// COBS-encodes and writes a reply frame out over the serial port. Best
+/// effort: if the host isn't reading, the write is dropped rather than
+/// blocking USB servicing.
+pub fn write_serial_frame(message: &crate::protocol::DeviceMessage) {
+    let mut encode_buf = [0u8; MAX_FRAME_LEN];
+    let Ok(frame) = postcard::to_slice_cobs(message, &mut encode_buf) else {
+        info!("Failed to encode DeviceMessage");
+        return;
+    };
+    critical_section::with(|cs| {
+        if let Some(ctx) = USB_CONTEXT.borrow(cs).borrow_mut().as_mut() {
+            let _ = ctx.serial.write(frame);
+        }
+    });
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USBCTRL_IRQ() {
+    critical_section::with(|cs| {
+        let mut ctx = USB_CONTEXT.borrow(cs).borrow_mut();
+        let Some(ctx) = ctx.as_mut() else {
+            return;
+        };
+
+        if ctx.device.poll(&mut [&mut ctx.hid, &mut ctx.serial]) {
+            let mut command = CustomHidCommand::default();
+            match ctx.hid.device().read_report(&mut command) {
+                Ok(()) => ctx.latest_command = Some(command),
+                Err(UsbHidError::WouldBlock) => {}
+                Err(UsbHidError::SerializationError) => {
+                    info!("Serialization error in received command");
+                }
+                Err(_) => {
+                    info!("Unknown error in received command");
+                }
+            }
+
+            let mut byte = [0u8; 1];
+            while let Ok(1) = ctx.serial.read(&mut byte) {
+                if byte[0] == 0 {
+                    if ctx.rx_buf.len() == MAX_FRAME_LEN {
+                        // Oversized/garbled frame: drop it and resync on
+                        // this delimiter.
+                        info!("Serial rx frame overflowed, dropping");
+                    } else {
+                        // COBS frame delimiter: decode what we've buffered.
+                        match postcard::from_bytes_cobs::<HostMessage>(&mut ctx.rx_buf) {
+                            Ok(message) => ctx.latest_message = Some(message),
+                            Err(_) => info!("Failed to decode HostMessage frame"),
+                        }
+                    }
+                    ctx.rx_buf.clear();
+                } else {
+                    // A full buffer means the frame is oversized (a valid
+                    // frame plus its delimiter fits in MAX_FRAME_LEN); the
+                    // rest of it is ignored until the next delimiter.
+                    let _ = ctx.rx_buf.push(byte[0]);
+                }
+            }
+        }
+
+        if let Err(_e) = ctx.hid.device().tick() {
+            info!("Error ticking CustomHid status report");
+        }
+    });
+}